@@ -8,7 +8,8 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use rustc::util::nodemap::NodeSet;
 use std::cmp;
 use std::string::String;
@@ -22,21 +23,42 @@ use plugins;
 use fold;
 use fold::DocFolder;
 
-/// Strip items marked `#[doc(hidden)]`
-pub fn strip_hidden(krate: clean::Crate) -> plugins::PluginResult {
+/// Nodes that `strip_hidden` found marked `#[doc(hidden)]` while running
+/// with `show_hidden_items` set, so the renderer can look items up by
+/// `NodeId` and flag them instead of `strip_hidden` deleting them outright.
+///
+/// Like `DOC_FRAGMENT_SPANS`, this lives in a thread-local side table
+/// rather than on `clean::Item` itself, because `clean::Item` isn't part of
+/// this crate snapshot to add a "hidden" field to.
+thread_local!(pub static HIDDEN_ITEMS: RefCell<HashSet<ast::NodeId>> = RefCell::new(HashSet::new()));
+
+/// Strip items marked `#[doc(hidden)]`.
+///
+/// When `show_hidden_items` is set (driven by `--show-hidden-items`),
+/// hidden items are kept and folded as usual instead of being deleted;
+/// they are recorded in `HIDDEN_ITEMS` so the renderer can mark them, and
+/// the `ImplStripper` phase below leaves their impls alone too.
+pub fn strip_hidden(krate: clean::Crate, show_hidden_items: bool) -> plugins::PluginResult {
     let mut stripped = HashSet::new();
 
     // strip all #[doc(hidden)] items
     let krate = {
         struct Stripper<'a> {
-            stripped: &'a mut HashSet<ast::NodeId>
+            stripped: &'a mut HashSet<ast::NodeId>,
+            show_hidden_items: bool,
         };
         impl<'a> fold::DocFolder for Stripper<'a> {
             fn fold_item(&mut self, i: Item) -> Option<Item> {
                 if i.is_hidden_from_doc() {
-                    debug!("found one in strip_hidden; removing");
                     self.stripped.insert(i.def_id.node);
 
+                    if self.show_hidden_items {
+                        debug!("found one in strip_hidden; marking as hidden");
+                        HIDDEN_ITEMS.with(|h| { h.borrow_mut().insert(i.def_id.node); });
+                        return self.fold_item_recur(i);
+                    }
+
+                    debug!("found one in strip_hidden; removing");
                     // use a dedicated hidden item for given item type if any
                     match i.inner {
                         clean::StructFieldItem(..) => {
@@ -54,36 +76,45 @@ pub fn strip_hidden(krate: clean::Crate) -> plugins::PluginResult {
                 self.fold_item_recur(i)
             }
         }
-        let mut stripper = Stripper{ stripped: &mut stripped };
+        let mut stripper = Stripper {
+            stripped: &mut stripped,
+            show_hidden_items: show_hidden_items,
+        };
         stripper.fold_crate(krate)
     };
 
     // strip any traits implemented on stripped items
     let krate = {
         struct ImplStripper<'a> {
-            stripped: &'a mut HashSet<ast::NodeId>
+            stripped: &'a mut HashSet<ast::NodeId>,
+            show_hidden_items: bool,
         };
         impl<'a> fold::DocFolder for ImplStripper<'a> {
             fn fold_item(&mut self, i: Item) -> Option<Item> {
-                if let clean::ImplItem(clean::Impl{
-                           for_: clean::ResolvedPath{ did, .. },
-                           ref trait_, ..
-                }) = i.inner {
-                    // Impls for stripped types don't need to exist
-                    if self.stripped.contains(&did.node) {
-                        return None;
-                    }
-                    // Impls of stripped traits also don't need to exist
-                    if let Some(clean::ResolvedPath { did, .. }) = *trait_ {
+                if !self.show_hidden_items {
+                    if let clean::ImplItem(clean::Impl{
+                               for_: clean::ResolvedPath{ did, .. },
+                               ref trait_, ..
+                    }) = i.inner {
+                        // Impls for stripped types don't need to exist
                         if self.stripped.contains(&did.node) {
                             return None;
                         }
+                        // Impls of stripped traits also don't need to exist
+                        if let Some(clean::ResolvedPath { did, .. }) = *trait_ {
+                            if self.stripped.contains(&did.node) {
+                                return None;
+                            }
+                        }
                     }
                 }
                 self.fold_item_recur(i)
             }
         }
-        let mut stripper = ImplStripper{ stripped: &mut stripped };
+        let mut stripper = ImplStripper {
+            stripped: &mut stripped,
+            show_hidden_items: show_hidden_items,
+        };
         stripper.fold_crate(krate)
     };
 
@@ -92,7 +123,12 @@ pub fn strip_hidden(krate: clean::Crate) -> plugins::PluginResult {
 
 /// Strip private items from the point of view of a crate or externally from a
 /// crate, specified by the `xcrate` flag.
-pub fn strip_private(mut krate: clean::Crate) -> plugins::PluginResult {
+///
+/// When `keep_private` is set (driven by `--document-private-items`), none of
+/// the folders below actually remove anything; items are folded as usual so
+/// that doc rendering still visits them, but their real `visibility` is left
+/// untouched instead of being discarded or papered over.
+pub fn strip_private(mut krate: clean::Crate, keep_private: bool) -> plugins::PluginResult {
     // This stripper collects all *retained* nodes.
     let mut retained = HashSet::new();
     let analysis = super::ANALYSISKEY.with(|a| a.clone());
@@ -105,13 +141,14 @@ pub fn strip_private(mut krate: clean::Crate) -> plugins::PluginResult {
         let mut stripper = Stripper {
             retained: &mut retained,
             exported_items: &exported_items,
+            keep_private: keep_private,
         };
         krate = stripper.fold_crate(krate);
     }
 
     // strip all private implementations of traits
     {
-        let mut stripper = ImplStripper(&retained);
+        let mut stripper = ImplStripper(&retained, keep_private);
         krate = stripper.fold_crate(krate);
     }
     (krate, None)
@@ -120,6 +157,9 @@ pub fn strip_private(mut krate: clean::Crate) -> plugins::PluginResult {
 struct Stripper<'a> {
     retained: &'a mut HashSet<ast::NodeId>,
     exported_items: &'a NodeSet,
+    // If set, nothing is actually stripped; items are kept with their real
+    // visibility so `--document-private-items` can render them.
+    keep_private: bool,
 }
 
 impl<'a> fold::DocFolder for Stripper<'a> {
@@ -131,7 +171,7 @@ impl<'a> fold::DocFolder for Stripper<'a> {
             clean::TraitItem(..) | clean::FunctionItem(..) |
             clean::VariantItem(..) | clean::MethodItem(..) |
             clean::ForeignFunctionItem(..) | clean::ForeignStaticItem(..) => {
-                if ast_util::is_local(i.def_id) {
+                if !self.keep_private && ast_util::is_local(i.def_id) {
                     if !self.exported_items.contains(&i.def_id.node) {
                         return None;
                     }
@@ -143,20 +183,20 @@ impl<'a> fold::DocFolder for Stripper<'a> {
             }
 
             clean::ConstantItem(..) => {
-                if ast_util::is_local(i.def_id) &&
+                if !self.keep_private && ast_util::is_local(i.def_id) &&
                    !self.exported_items.contains(&i.def_id.node) {
                     return None;
                 }
             }
 
             clean::ExternCrateItem(..) | clean::ImportItem(_) => {
-                if i.visibility != Some(ast::Public) {
+                if !self.keep_private && i.visibility != Some(ast::Public) {
                     return None
                 }
             }
 
             clean::StructFieldItem(..) => {
-                if i.visibility != Some(ast::Public) {
+                if !self.keep_private && i.visibility != Some(ast::Public) {
                     return Some(clean::Item {
                         inner: clean::StructFieldItem(clean::HiddenStructField),
                         ..i
@@ -171,7 +211,7 @@ impl<'a> fold::DocFolder for Stripper<'a> {
             clean::ImplItem(clean::Impl{
                 for_: clean::ResolvedPath{ did, .. }, ..
             }) => {
-                if ast_util::is_local(did) &&
+                if !self.keep_private && ast_util::is_local(did) &&
                    !self.exported_items.contains(&did.node) {
                     return None;
                 }
@@ -214,11 +254,12 @@ impl<'a> fold::DocFolder for Stripper<'a> {
         match i {
             Some(i) => {
                 match i.inner {
-                    // emptied modules/impls have no need to exist
+                    // emptied modules/impls have no need to exist, unless
+                    // we're keeping private items around on purpose
                     clean::ModuleItem(ref m)
-                        if m.items.is_empty() &&
+                        if !self.keep_private && m.items.is_empty() &&
                            i.doc_value().is_none() => None,
-                    clean::ImplItem(ref i) if i.items.is_empty() => None,
+                    clean::ImplItem(ref i) if !self.keep_private && i.items.is_empty() => None,
                     _ => {
                         self.retained.insert(i.def_id.node);
                         Some(i)
@@ -230,15 +271,16 @@ impl<'a> fold::DocFolder for Stripper<'a> {
     }
 }
 
-// This stripper discards all private impls of traits
-struct ImplStripper<'a>(&'a HashSet<ast::NodeId>);
+// This stripper discards all private impls of traits, unless `keep_private`
+// is set, in which case it leaves everything alone.
+struct ImplStripper<'a>(&'a HashSet<ast::NodeId>, bool);
 impl<'a> fold::DocFolder for ImplStripper<'a> {
     fn fold_item(&mut self, i: Item) -> Option<Item> {
         if let clean::ImplItem(ref imp) = i.inner {
             match imp.trait_ {
                 Some(clean::ResolvedPath{ did, .. }) => {
-                    let ImplStripper(s) = *self;
-                    if ast_util::is_local(did) && !s.contains(&did.node) {
+                    let ImplStripper(s, keep_private) = *self;
+                    if !keep_private && ast_util::is_local(did) && !s.contains(&did.node) {
                         return None;
                     }
                 }
@@ -275,18 +317,60 @@ pub fn unindent_comments(krate: clean::Crate) -> plugins::PluginResult {
     (krate, None)
 }
 
+/// One source `#[doc]` fragment's extent within the collapsed doc string
+/// `collapse_docs` builds for an item.
+///
+/// This is *not* a real source location, only where the fragment landed in
+/// the collapsed string. `clean::Attribute`'s `NameValue` variant carries no
+/// span to draw a real one from, and guessing a position from `Item::source`
+/// doesn't work in general: inner (`//!`) doc comments live at or below the
+/// item's own start line rather than above it (so for a crate root, whose
+/// `source.loline` is line 1, "walk backward from the item" runs off the
+/// start of the file), and even for outer (`///`) doc comments, intervening
+/// attributes or blank lines between the comment and the item break the
+/// "immediately above" assumption. So this table sticks to what it can
+/// state honestly: `attr_index` is this fragment's position within the
+/// item's *full* attribute list (`Item::attrs`, not just its doc
+/// fragments) as it stood before `collapse_docs` replaced that list with
+/// the non-doc attributes plus one collapsed doc attribute, so it does not
+/// index the item's attributes after this pass; `start_line`/`end_line` are offsets into
+/// the collapsed string, useful only for locating which fragment produced
+/// a given collapsed line, not where that line lives in the user's source.
+#[derive(Clone, Debug)]
+pub struct FragmentSpan {
+    /// Index of this fragment within the item's full attribute list
+    /// (`Item::attrs` before `collapse_docs` rewrites it), not just among
+    /// its doc fragments.
+    pub attr_index: usize,
+    /// First line of this fragment within the collapsed doc string.
+    pub start_line: usize,
+    /// One past the last line of this fragment within the collapsed doc
+    /// string.
+    pub end_line: usize,
+}
+
+thread_local!(pub static DOC_FRAGMENT_SPANS: RefCell<HashMap<ast::NodeId, Vec<FragmentSpan>>> =
+              RefCell::new(HashMap::new()));
+
 pub fn collapse_docs(krate: clean::Crate) -> plugins::PluginResult {
     struct Collapser;
     impl fold::DocFolder for Collapser {
         fn fold_item(&mut self, i: Item) -> Option<Item> {
             let mut docstr = String::new();
+            let mut spans = Vec::new();
             let mut i = i;
-            for attr in &i.attrs {
+            for (attr_index, attr) in i.attrs.iter().enumerate() {
                 match *attr {
                     clean::NameValue(ref x, ref s)
                             if "doc" == *x => {
+                        let start_line = docstr.lines_any().count();
                         docstr.push_str(s);
                         docstr.push('\n');
+                        spans.push(FragmentSpan {
+                            attr_index: attr_index,
+                            start_line: start_line,
+                            end_line: docstr.lines_any().count(),
+                        });
                     },
                     _ => ()
                 }
@@ -298,6 +382,11 @@ pub fn collapse_docs(krate: clean::Crate) -> plugins::PluginResult {
             if !docstr.is_empty() {
                 a.push(clean::NameValue("doc".to_string(), docstr));
             }
+            if !spans.is_empty() {
+                DOC_FRAGMENT_SPANS.with(|m| {
+                    m.borrow_mut().insert(i.def_id.node, spans);
+                });
+            }
             i.attrs = a;
             self.fold_item_recur(i)
         }
@@ -307,6 +396,56 @@ pub fn collapse_docs(krate: clean::Crate) -> plugins::PluginResult {
     (krate, None)
 }
 
+// Tabs expand to the next multiple of this width, matching rustc's own
+// default tab stop, so indentation measured in tabs and indentation
+// measured in spaces land on comparable columns.
+const TAB_STOP: usize = 8;
+
+/// Returns the column reached after consuming the leading run of spaces and
+/// tabs in `line`.
+fn indent_columns(line: &str) -> usize {
+    let mut columns = 0;
+    for c in line.chars() {
+        match c {
+            ' ' => columns += 1,
+            '\t' => columns += TAB_STOP - columns % TAB_STOP,
+            _ => break,
+        }
+    }
+    columns
+}
+
+/// Strips `columns` columns' worth of leading whitespace from `line`. If a
+/// tab straddles the cut point, the portion of it past `columns` is
+/// re-materialized as spaces so the remaining indentation lines up.
+fn strip_indent_columns(line: &str, columns: usize) -> String {
+    let mut column = 0;
+    for (byte_idx, c) in line.char_indices() {
+        if column >= columns {
+            return line[byte_idx..].to_string();
+        }
+        match c {
+            ' ' => column += 1,
+            '\t' => {
+                let next_stop = column + (TAB_STOP - column % TAB_STOP);
+                if next_stop > columns {
+                    let mut s = String::new();
+                    for _ in 0..(next_stop - columns) {
+                        s.push(' ');
+                    }
+                    s.push_str(&line[(byte_idx + 1)..]);
+                    return s;
+                }
+                column = next_stop;
+            }
+            // Ran out of leading whitespace before reaching `columns`;
+            // the `assert!` below guards against this happening.
+            _ => unreachable!(),
+        }
+    }
+    String::new()
+}
+
 pub fn unindent(s: &str) -> String {
     let lines = s.lines_any().collect::<Vec<&str> >();
     let mut saw_first_line = false;
@@ -336,18 +475,7 @@ pub fn unindent(s: &str) -> String {
             min_indent
         } else {
             saw_first_line = true;
-            let mut spaces = 0;
-            line.chars().all(|char| {
-                // Only comparing against space because I wouldn't
-                // know what to do with mixed whitespace chars
-                if char == ' ' {
-                    spaces += 1;
-                    true
-                } else {
-                    false
-                }
-            });
-            cmp::min(min_indent, spaces)
+            cmp::min(min_indent, indent_columns(line))
         }
     });
 
@@ -357,8 +485,8 @@ pub fn unindent(s: &str) -> String {
             if line.chars().all(|c| c.is_whitespace()) {
                 line.to_string()
             } else {
-                assert!(line.len() >= min_indent);
-                line[min_indent..].to_string()
+                assert!(indent_columns(line) >= min_indent);
+                strip_indent_columns(line, min_indent)
             }
         }).collect::<Vec<_>>());
         unindented.join("\n")
@@ -412,4 +540,30 @@ mod unindent_tests {
         let r = unindent(&s);
         assert_eq!(r, "line1\n\n    line2");
     }
+
+    #[test]
+    fn should_unindent_tabs() {
+        let s = "\tline1\n\tline2".to_string();
+        let r = unindent(&s);
+        assert_eq!(r, "line1\nline2");
+    }
+
+    #[test]
+    fn should_unindent_mixed_tabs_and_spaces_at_the_same_column() {
+        // Two spaces plus a tab both land on column 8, same as a lone tab,
+        // so both lines are considered equally indented.
+        let s = "  \tline1\n  \tline2".to_string();
+        let r = unindent(&s);
+        assert_eq!(r, "line1\nline2");
+    }
+
+    #[test]
+    fn should_rematerialize_a_split_tab_as_spaces() {
+        // Base indentation is 2 columns, but line2's tab jumps straight to
+        // column 8; the leftover 6 columns of that tab should come back as
+        // spaces rather than being dropped or left as a tab character.
+        let s = "  line1\n\n\tline2".to_string();
+        let r = unindent(&s);
+        assert_eq!(r, "line1\n\n      line2");
+    }
 }